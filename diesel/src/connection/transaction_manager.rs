@@ -35,7 +35,70 @@ pub trait TransactionManager<Conn: Connection> {
     fn get_transaction_depth(&self) -> u32;
 }
 
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+
+/// Describes the SQL a backend uses to begin, commit, and roll back
+/// transactions and savepoints, so `AnsiTransactionManager` can reuse its
+/// depth tracking for a non-ANSI dialect (e.g. SQL Server).
+///
+/// `name` is `None` for the outermost transaction, and `Some` with the
+/// actual savepoint name otherwise.
+pub trait SavepointSyntax {
+    /// SQL to start a new transaction or savepoint.
+    fn begin_statement(name: Option<&str>) -> String;
+
+    /// SQL to commit a transaction, or release a savepoint.
+    ///
+    /// Returns `None` when the dialect has no way to release a savepoint
+    /// (e.g. SQL Server), making that commit a no-op. Always `Some` when
+    /// `name` is `None`.
+    fn commit_statement(name: Option<&str>) -> Option<String>;
+
+    /// SQL to roll back a transaction, or roll back to a savepoint.
+    fn rollback_statement(name: Option<&str>) -> String;
+}
+
+/// The standard ANSI dialect, used by backends that implement
+/// [`UsesAnsiSavepointSyntax`] such as SQLite and PostgreSQL.
+#[allow(missing_debug_implementations)]
+pub struct AnsiSavepointSyntax;
+
+impl SavepointSyntax for AnsiSavepointSyntax {
+    fn begin_statement(name: Option<&str>) -> String {
+        match name {
+            None => "BEGIN".into(),
+            Some(name) => format!("SAVEPOINT {}", name),
+        }
+    }
+
+    fn commit_statement(name: Option<&str>) -> Option<String> {
+        Some(match name {
+            None => "COMMIT".into(),
+            Some(name) => format!("RELEASE SAVEPOINT {}", name),
+        })
+    }
+
+    fn rollback_statement(name: Option<&str>) -> String {
+        match name {
+            None => "ROLLBACK".into(),
+            Some(name) => format!("ROLLBACK TO SAVEPOINT {}", name),
+        }
+    }
+}
+
+/// Associates a [`Backend`](crate::backend::Backend) with the
+/// [`SavepointSyntax`] it speaks.
+///
+/// Backends that implement [`UsesAnsiSavepointSyntax`] get this for free
+/// via [`AnsiSavepointSyntax`]; others implement it directly.
+pub trait HasSavepointSyntax {
+    /// The dialect this backend speaks.
+    type Syntax: SavepointSyntax;
+}
+
+impl<B: UsesAnsiSavepointSyntax> HasSavepointSyntax for B {
+    type Syntax = AnsiSavepointSyntax;
+}
 
 /// An implementation of `TransactionManager` which can be used for backends
 /// which use ANSI standard syntax for savepoints such as SQLite and PostgreSQL.
@@ -43,6 +106,7 @@ use std::cell::Cell;
 #[derive(Default)]
 pub struct AnsiTransactionManager {
     transaction_depth: Cell<i32>,
+    savepoint_names: RefCell<Vec<String>>,
 }
 
 impl AnsiTransactionManager {
@@ -76,38 +140,161 @@ impl AnsiTransactionManager {
             Err(AlreadyInTransaction)
         }
     }
+
+    /// Begin a savepoint with an explicit, caller-chosen name.
+    ///
+    /// Unlike the anonymous savepoints `begin_transaction` creates on nested
+    /// calls, this lets `rollback_to_savepoint`/`release_savepoint` target it
+    /// later regardless of what gets nested inside it afterwards. Errors if
+    /// no transaction is open, or if `name` is not a valid identifier.
+    pub fn begin_named_savepoint<Conn>(&self, conn: &Conn, name: &str) -> QueryResult<()>
+    where
+        Conn: SimpleConnection,
+    {
+        if self.transaction_depth.get() == 0 {
+            return Err(not_in_transaction_error());
+        }
+        validate_savepoint_name(name)?;
+
+        let query = conn.batch_execute(&format!("SAVEPOINT {}", name));
+        if query.is_ok() {
+            self.savepoint_names.borrow_mut().push(name.to_owned());
+        }
+        self.change_transaction_depth(1, query)
+    }
+
+    /// Roll back to a previously named savepoint, discarding anything
+    /// nested inside it. The named savepoint itself stays established
+    /// afterwards. Errors if no transaction is open, `name` is not a valid
+    /// identifier, or no savepoint named `name` is currently open.
+    pub fn rollback_to_savepoint<Conn>(&self, conn: &Conn, name: &str) -> QueryResult<()>
+    where
+        Conn: SimpleConnection,
+    {
+        if self.transaction_depth.get() == 0 {
+            return Err(not_in_transaction_error());
+        }
+        validate_savepoint_name(name)?;
+        let position = self
+            .savepoint_names
+            .borrow()
+            .iter()
+            .rposition(|n| n == name)
+            .ok_or_else(|| unknown_savepoint_error(name))?;
+
+        let query = conn.batch_execute(&format!("ROLLBACK TO SAVEPOINT {}", name));
+        if query.is_ok() {
+            let mut savepoint_names = self.savepoint_names.borrow_mut();
+            // Only the levels *nested inside* the named savepoint are
+            // discarded; the named savepoint itself is still established
+            // afterwards, so it is kept on the stack.
+            let levels_discarded = savepoint_names.len() - (position + 1);
+            savepoint_names.truncate(position + 1);
+            drop(savepoint_names);
+            return self.change_transaction_depth(-(levels_discarded as i32), Ok(()));
+        }
+        query
+    }
+
+    /// Release a previously named savepoint, along with everything nested
+    /// inside it. Errors if no transaction is open, `name` is not a valid
+    /// identifier, or no savepoint named `name` is currently open.
+    pub fn release_savepoint<Conn>(&self, conn: &Conn, name: &str) -> QueryResult<()>
+    where
+        Conn: SimpleConnection,
+    {
+        if self.transaction_depth.get() == 0 {
+            return Err(not_in_transaction_error());
+        }
+        validate_savepoint_name(name)?;
+        let position = self
+            .savepoint_names
+            .borrow()
+            .iter()
+            .rposition(|n| n == name)
+            .ok_or_else(|| unknown_savepoint_error(name))?;
+
+        let query = conn.batch_execute(&format!("RELEASE SAVEPOINT {}", name));
+        if query.is_err() {
+            return query;
+        }
+
+        let mut savepoint_names = self.savepoint_names.borrow_mut();
+        let levels_released = savepoint_names.len() - position;
+        savepoint_names.truncate(position);
+        drop(savepoint_names);
+        self.change_transaction_depth(-(levels_released as i32), query)
+    }
+}
+
+fn not_in_transaction_error() -> Error {
+    Error::QueryBuilderError("no transaction is open".into())
+}
+
+fn unknown_savepoint_error(name: &str) -> Error {
+    Error::QueryBuilderError(format!("no savepoint named `{}` is open", name).into())
+}
+
+/// Validates that `name` is safe to interpolate into a savepoint statement;
+/// savepoint names can't be bound as query parameters.
+fn validate_savepoint_name(name: &str) -> QueryResult<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(Error::QueryBuilderError(
+            format!(
+                "`{}` is not a valid savepoint name, only ASCII letters, digits and underscores are allowed",
+                name
+            )
+            .into(),
+        ));
+    }
+    Ok(())
 }
 
 impl<Conn> TransactionManager<Conn> for AnsiTransactionManager
 where
     Conn: Connection,
-    Conn::Backend: UsesAnsiSavepointSyntax,
+    Conn::Backend: HasSavepointSyntax,
 {
     fn begin_transaction(&self, conn: &Conn) -> QueryResult<()> {
+        type Syntax<Conn> = <<Conn as Connection>::Backend as HasSavepointSyntax>::Syntax;
+
         let transaction_depth = self.transaction_depth.get();
-        self.change_transaction_depth(
-            1,
-            if transaction_depth == 0 {
-                conn.batch_execute("BEGIN")
-            } else {
-                conn.batch_execute(&format!("SAVEPOINT diesel_savepoint_{}", transaction_depth))
-            },
-        )
+        if transaction_depth == 0 {
+            let query = conn.batch_execute(&Syntax::<Conn>::begin_statement(None));
+            self.change_transaction_depth(1, query)
+        } else {
+            let name = format!("diesel_savepoint_{}", transaction_depth);
+            let result = conn.batch_execute(&Syntax::<Conn>::begin_statement(Some(&name)));
+            if result.is_ok() {
+                self.savepoint_names.borrow_mut().push(name);
+            }
+            self.change_transaction_depth(1, result)
+        }
     }
 
     fn rollback_transaction(&self, conn: &Conn) -> QueryResult<()> {
+        type Syntax<Conn> = <<Conn as Connection>::Backend as HasSavepointSyntax>::Syntax;
+
         let transaction_depth = self.transaction_depth.get();
-        self.change_transaction_depth(
-            -1,
-            if transaction_depth == 1 {
-                conn.batch_execute("ROLLBACK")
-            } else {
-                conn.batch_execute(&format!(
-                    "ROLLBACK TO SAVEPOINT diesel_savepoint_{}",
-                    transaction_depth - 1
-                ))
-            },
-        )
+        if transaction_depth <= 1 {
+            let query = conn.batch_execute(&Syntax::<Conn>::rollback_statement(None));
+            self.change_transaction_depth(-1, query)
+        } else {
+            // Target the savepoint actually tracked at this depth, not one
+            // re-derived from the depth: a named savepoint may have been
+            // opened here instead of the usual `diesel_savepoint_N`.
+            let name = self
+                .savepoint_names
+                .borrow()
+                .last()
+                .cloned()
+                .expect("savepoint stack out of sync with transaction depth");
+            let result = conn.batch_execute(&Syntax::<Conn>::rollback_statement(Some(&name)));
+            if result.is_ok() {
+                self.savepoint_names.borrow_mut().pop();
+            }
+            self.change_transaction_depth(-1, result)
+        }
     }
 
     /// If the transaction fails to commit due to a `SerializationFailure` or a
@@ -116,27 +303,43 @@ where
     /// will be returned. In the second case the connection should be considered broken
     /// as it contains a uncommitted unabortable open transaction.
     fn commit_transaction(&self, conn: &Conn) -> QueryResult<()> {
+        type Syntax<Conn> = <<Conn as Connection>::Backend as HasSavepointSyntax>::Syntax;
+
         let transaction_depth = self.transaction_depth.get();
         if transaction_depth <= 1 {
-            match conn.batch_execute("COMMIT") {
+            let statement = Syntax::<Conn>::commit_statement(None)
+                .expect("a top-level commit statement is never a no-op");
+            match conn.batch_execute(&statement) {
                 // When any of these kinds of error happen on `COMMIT`, it is expected
                 // that a `ROLLBACK` would succeed, leaving the transaction in a non-broken state.
                 // If there are other such errors, it is fine to add them here.
                 e @ Err(Error::DatabaseError(DatabaseErrorKind::SerializationFailure, _))
                 | e @ Err(Error::DatabaseError(DatabaseErrorKind::ReadOnlyTransaction, _)) => {
-                    self.change_transaction_depth(-1, conn.batch_execute("ROLLBACK"))?;
+                    let rollback_statement = Syntax::<Conn>::rollback_statement(None);
+                    self.change_transaction_depth(-1, conn.batch_execute(&rollback_statement))?;
                     e
                 }
                 result => self.change_transaction_depth(-1, result),
             }
         } else {
-            self.change_transaction_depth(
-                -1,
-                conn.batch_execute(&format!(
-                    "RELEASE SAVEPOINT diesel_savepoint_{}",
-                    transaction_depth - 1
-                )),
-            )
+            // Target the savepoint actually tracked at this depth (see
+            // `rollback_transaction`), not one re-derived from the depth.
+            let name = self
+                .savepoint_names
+                .borrow()
+                .last()
+                .cloned()
+                .expect("savepoint stack out of sync with transaction depth");
+            // A dialect without `RELEASE SAVEPOINT` (e.g. SQL Server) returns
+            // `None` here; releasing that savepoint is then a pure no-op.
+            let result = match Syntax::<Conn>::commit_statement(Some(&name)) {
+                Some(statement) => conn.batch_execute(&statement),
+                None => Ok(()),
+            };
+            if result.is_ok() {
+                self.savepoint_names.borrow_mut().pop();
+            }
+            self.change_transaction_depth(-1, result)
         }
     }
 
@@ -145,8 +348,227 @@ where
     }
 }
 
+use std::time::Duration;
+
+/// Backoff strategy to wait between attempts of [`transaction_with_retry`].
+#[derive(Debug, Clone, Copy)]
+pub enum RetryBackoff {
+    /// Wait the same duration before every retry.
+    Constant(Duration),
+    /// Double the wait duration after every retry, starting from the given duration.
+    Exponential(Duration),
+}
+
+impl RetryBackoff {
+    fn duration_for_attempt(self, attempt: u32) -> Duration {
+        match self {
+            RetryBackoff::Constant(duration) => duration,
+            RetryBackoff::Exponential(duration) => {
+                // Cap the exponent so the multiplier itself can't overflow,
+                // and fall back to the last non-overflowing duration rather
+                // than panic if multiplying by it still would.
+                let mut multiplier = 2u32.saturating_pow(attempt.min(20));
+                loop {
+                    if let Some(wait) = duration.checked_mul(multiplier) {
+                        break wait;
+                    }
+                    multiplier /= 2;
+                }
+            }
+        }
+    }
+}
+
+/// Run `f` inside a fresh `SERIALIZABLE` Postgres transaction, retrying from
+/// scratch whenever it fails to commit (or fails partway through) with a
+/// `SerializationFailure` database error.
+///
+/// Every attempt begins its own top-level transaction with
+/// `BEGIN ISOLATION LEVEL SERIALIZABLE` (via `begin_transaction_sql`), so the
+/// database is expected to abort one of several conflicting transactions
+/// rather than let them interleave unsafely; callers using `PgConnection` do
+/// not need to configure the connection's isolation level themselves. `f`
+/// must be idempotent, since it will be called again from scratch on every
+/// attempt. Rolls back the failed transaction (bringing
+/// `get_transaction_depth` back to 0) before each retry. Gives up and
+/// returns the last error after `max_retries` retries. Never retries on any
+/// other kind of error.
+///
+/// `BEGIN ISOLATION LEVEL SERIALIZABLE` is PostgreSQL syntax, so this is
+/// only available for `PgConnection`.
+pub fn transaction_with_retry<Conn, T, F>(
+    conn: &Conn,
+    max_retries: u32,
+    backoff: Option<RetryBackoff>,
+    mut f: F,
+) -> QueryResult<T>
+where
+    Conn: Connection<Backend = crate::pg::Pg, TransactionManager = AnsiTransactionManager>
+        + SimpleConnection,
+    F: FnMut(&Conn) -> QueryResult<T>,
+{
+    let mut attempt = 0;
+    loop {
+        let manager = conn.transaction_manager();
+        manager.begin_transaction_sql(conn, "BEGIN ISOLATION LEVEL SERIALIZABLE")?;
+
+        let result = f(conn).and_then(|value| manager.commit_transaction(conn).map(|_| value));
+
+        match result {
+            Ok(value) => return Ok(value),
+            Err(e @ Error::DatabaseError(DatabaseErrorKind::SerializationFailure, _)) => {
+                if manager.get_transaction_depth() > 0 {
+                    manager.rollback_transaction(conn)?;
+                }
+                if attempt >= max_retries {
+                    return Err(e);
+                }
+                if let Some(backoff) = backoff {
+                    std::thread::sleep(backoff.duration_for_attempt(attempt));
+                }
+                attempt += 1;
+            }
+            Err(e) => {
+                if manager.get_transaction_depth() > 0 {
+                    let _ = manager.rollback_transaction(conn);
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use super::AnsiTransactionManager;
+    use crate::connection::SimpleConnection;
+    use crate::result::QueryResult;
+
+    struct NoopConnection;
+
+    impl SimpleConnection for NoopConnection {
+        fn batch_execute(&self, _query: &str) -> QueryResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rollback_to_named_savepoint_keeps_it_but_drops_nested_levels() {
+        let manager = AnsiTransactionManager::new();
+        let conn = NoopConnection;
+
+        manager.begin_transaction_sql(&conn, "BEGIN").unwrap();
+        manager.begin_named_savepoint(&conn, "a").unwrap();
+        manager.begin_named_savepoint(&conn, "b").unwrap();
+        assert_eq!(3, manager.transaction_depth.get());
+
+        manager.rollback_to_savepoint(&conn, "a").unwrap();
+
+        // "b" is discarded, but "a" is still established, so the depth is
+        // back to just after "a" was opened, not all the way to 1.
+        assert_eq!(2, manager.transaction_depth.get());
+        assert_eq!(
+            vec!["a".to_owned()],
+            *manager.savepoint_names.borrow()
+        );
+
+        // "a" is still usable afterwards.
+        manager.rollback_to_savepoint(&conn, "a").unwrap();
+        assert_eq!(2, manager.transaction_depth.get());
+    }
+
+    #[test]
+    fn release_named_savepoint_drops_every_nested_level() {
+        let manager = AnsiTransactionManager::new();
+        let conn = NoopConnection;
+
+        manager.begin_transaction_sql(&conn, "BEGIN").unwrap();
+        manager.begin_named_savepoint(&conn, "a").unwrap();
+        manager.begin_named_savepoint(&conn, "b").unwrap();
+        assert_eq!(3, manager.transaction_depth.get());
+
+        manager.release_savepoint(&conn, "a").unwrap();
+
+        // Releasing "a" also releases "b", which was nested inside it, so
+        // the depth drops by two levels, not just one.
+        assert_eq!(1, manager.transaction_depth.get());
+        assert!(manager.savepoint_names.borrow().is_empty());
+    }
+
+    #[test]
+    fn named_savepoint_helpers_reject_unsafe_identifiers() {
+        let manager = AnsiTransactionManager::new();
+        let conn = NoopConnection;
+
+        manager.begin_transaction_sql(&conn, "BEGIN").unwrap();
+
+        assert!(manager.begin_named_savepoint(&conn, "").is_err());
+        assert!(manager
+            .begin_named_savepoint(&conn, "a; DROP TABLE users; --")
+            .is_err());
+        assert_eq!(1, manager.transaction_depth.get());
+
+        manager.begin_named_savepoint(&conn, "a").unwrap();
+        assert!(manager.rollback_to_savepoint(&conn, "'; --").is_err());
+        assert!(manager.release_savepoint(&conn, "a b").is_err());
+    }
+
+    #[test]
+    fn named_savepoint_helpers_reject_unknown_names() {
+        let manager = AnsiTransactionManager::new();
+        let conn = NoopConnection;
+
+        manager.begin_transaction_sql(&conn, "BEGIN").unwrap();
+        manager.begin_named_savepoint(&conn, "a").unwrap();
+
+        assert!(manager.rollback_to_savepoint(&conn, "never_opened").is_err());
+        assert!(manager.release_savepoint(&conn, "never_opened").is_err());
+        // The failed calls above must not have perturbed the real state.
+        assert_eq!(2, manager.transaction_depth.get());
+        assert_eq!(vec!["a".to_owned()], *manager.savepoint_names.borrow());
+    }
+
+    #[test]
+    #[cfg(feature = "postgres")]
+    fn mixing_named_and_anonymous_savepoints_targets_the_right_savepoint() {
+        use crate::connection::transaction_manager::{AnsiTransactionManager, TransactionManager};
+        use crate::*;
+
+        let conn = crate::test_helpers::pg_connection_no_transaction();
+        let manager = &conn.transaction_manager;
+
+        manager.begin_transaction(&conn).unwrap(); // depth 1, BEGIN
+        manager.begin_named_savepoint(&conn, "foo").unwrap(); // depth 2, SAVEPOINT foo
+        manager.begin_transaction(&conn).unwrap(); // depth 3, SAVEPOINT diesel_savepoint_2
+
+        manager.commit_transaction(&conn).unwrap(); // releases diesel_savepoint_2
+        assert_eq!(
+            2,
+            <AnsiTransactionManager as TransactionManager<PgConnection>>::get_transaction_depth(
+                &conn.transaction_manager
+            )
+        );
+
+        // This must release "foo", the savepoint actually tracked at this
+        // depth, not a depth-derived `diesel_savepoint_1` that was never
+        // opened.
+        manager.commit_transaction(&conn).unwrap();
+        assert_eq!(
+            1,
+            <AnsiTransactionManager as TransactionManager<PgConnection>>::get_transaction_depth(
+                &conn.transaction_manager
+            )
+        );
+
+        manager.rollback_transaction(&conn).unwrap();
+        assert_eq!(
+            0,
+            <AnsiTransactionManager as TransactionManager<PgConnection>>::get_transaction_depth(
+                &conn.transaction_manager
+            )
+        );
+    }
+
     #[cfg(feature = "postgres")]
     macro_rules! matches {
         ($expression:expr, $( $pattern:pat )|+ $( if $guard: expr )?) => {
@@ -244,4 +666,95 @@ mod test {
             Err(DatabaseError(SerializationFailure, _))
         ));
     }
+
+    #[test]
+    #[cfg(feature = "postgres")]
+    fn transaction_with_retry_retries_both_sides_of_a_serialization_failure() {
+        use crate::connection::transaction_manager::transaction_with_retry;
+        use crate::*;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::{Arc, Barrier};
+        use std::thread;
+
+        table! {
+            #[sql_name = "transaction_with_retry_retries_both_sides_of_a_serialization_failure"]
+            retry_example {
+                id -> Serial,
+                class -> Integer,
+            }
+        }
+
+        let conn = crate::test_helpers::pg_connection_no_transaction();
+
+        sql_query(
+            "DROP TABLE IF EXISTS transaction_with_retry_retries_both_sides_of_a_serialization_failure;",
+        )
+        .execute(&conn)
+        .unwrap();
+        sql_query(
+            r#"
+            CREATE TABLE transaction_with_retry_retries_both_sides_of_a_serialization_failure (
+                id SERIAL PRIMARY KEY,
+                class INTEGER NOT NULL
+            )
+        "#,
+        )
+        .execute(&conn)
+        .unwrap();
+
+        insert_into(retry_example::table)
+            .values(&vec![
+                retry_example::class.eq(1),
+                retry_example::class.eq(2),
+            ])
+            .execute(&conn)
+            .unwrap();
+
+        // Every thread only rendezvous with the other on its *first* attempt,
+        // so a retried attempt doesn't deadlock waiting on a barrier the
+        // other thread has already passed.
+        let barrier = Arc::new(Barrier::new(2));
+        let threads = (1..3)
+            .map(|i| {
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    use crate::connection::transaction_manager::AnsiTransactionManager;
+                    use crate::connection::transaction_manager::TransactionManager;
+
+                    let conn = crate::test_helpers::pg_connection_no_transaction();
+                    let waited = AtomicBool::new(false);
+
+                    let result = transaction_with_retry(&conn, 3, None, |conn| {
+                        let _ = retry_example::table
+                            .filter(retry_example::class.eq(i))
+                            .count()
+                            .execute(conn)?;
+
+                        if !waited.swap(true, Ordering::SeqCst) {
+                            barrier.wait();
+                        }
+
+                        let other_i = if i == 1 { 2 } else { 1 };
+                        insert_into(retry_example::table)
+                            .values(retry_example::class.eq(other_i))
+                            .execute(conn)
+                    });
+
+                    assert_eq!(0, <AnsiTransactionManager as TransactionManager<PgConnection>>::get_transaction_depth(&conn.transaction_manager));
+                    result
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let results = threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .collect::<Vec<_>>();
+
+        assert!(
+            results.iter().all(|r| r.is_ok()),
+            "expected both sides to eventually succeed via retry, got {:?}",
+            results
+        );
+    }
 }